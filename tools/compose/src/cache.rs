@@ -0,0 +1,134 @@
+//! A persistent, content-addressed cache so repeated runs skip files that
+//! have not changed since the last export, stored as a JSON sidecar under
+//! `out_path`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::ComposeOpts;
+
+pub(crate) const CACHE_FILE: &str = ".compose-cache.json";
+
+/// Bump whenever a change to the composer can alter a file's output
+/// without changing the file's own bytes or the options below.
+const TOOL_VERSION: u32 = 1;
+
+/// The options that feed into how a file is processed, folded into its
+/// hash so changing them invalidates the cache even if the file itself
+/// did not change. Only `no_fmt` affects `process_file`'s output, so
+/// that's all that belongs here: the rest of `ComposeOpts` and `Config`
+/// (e.g. `force`, `verify_modes`, `lint`) must stay out or they'd
+/// invalidate every cached file whenever they change.
+#[derive(Serialize)]
+struct CacheKey {
+    tool_version: u32,
+    no_fmt: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Manifest {
+    entries: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    input_hash: String,
+    output_exists: bool,
+}
+
+/// Tracks which inputs still match their last recorded hash, so
+/// `process_file` can be skipped for them.
+pub struct Cache {
+    base_in: PathBuf,
+    sidecar: PathBuf,
+    manifest: Manifest,
+    force: bool,
+}
+
+impl Cache {
+    /// Load the sidecar manifest under `args.out_path`, or start from an
+    /// empty one if `--force` was passed or none exists yet.
+    pub fn load(args: &ComposeOpts) -> Self {
+        let sidecar = args.out_path.join(CACHE_FILE);
+        let manifest = if args.force {
+            Manifest::default()
+        } else {
+            fs::read(&sidecar)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+                .unwrap_or_default()
+        };
+
+        Cache {
+            base_in: args.in_path.clone(),
+            sidecar,
+            manifest,
+            force: args.force,
+        }
+    }
+
+    /// Returns `true` if `in_path` can be skipped: its hash matches the
+    /// last recorded one and its output is still on disk.
+    pub fn is_fresh(&self, in_path: &Path, out_path: &Path, no_fmt: bool) -> Result<bool> {
+        if self.force || !out_path.exists() {
+            return Ok(false);
+        }
+
+        let rel = self.rel_path(in_path)?;
+        let prev = match self.manifest.entries.get(&rel) {
+            Some(prev) => prev,
+            None => return Ok(false),
+        };
+
+        Ok(prev.output_exists && prev.input_hash == hash_input(in_path, no_fmt)?)
+    }
+
+    /// Record the current hash of `in_path` after it has been processed.
+    pub fn record(&mut self, in_path: &Path, out_path: &Path, no_fmt: bool) -> Result<()> {
+        let rel = self.rel_path(in_path)?;
+        let entry = Entry {
+            input_hash: hash_input(in_path, no_fmt)?,
+            output_exists: out_path.exists(),
+        };
+        self.manifest.entries.insert(rel, entry);
+        Ok(())
+    }
+
+    /// Write the manifest back to its sidecar file.
+    pub fn save(&self) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(&self.manifest)
+            .context("failed to serialize cache manifest")?;
+        fs::write(&self.sidecar, bytes)
+            .with_context(|| format!("failed to write cache manifest {}", self.sidecar.display()))
+    }
+
+    fn rel_path(&self, in_path: &Path) -> Result<PathBuf> {
+        in_path.strip_prefix(&self.base_in).map(Path::to_path_buf).with_context(|| {
+            format!(
+                "{} is outside of {}",
+                in_path.display(),
+                self.base_in.display()
+            )
+        })
+    }
+}
+
+fn hash_input(in_path: &Path, no_fmt: bool) -> Result<String> {
+    let bytes = fs::read(in_path)
+        .with_context(|| format!("failed to read {} for hashing", in_path.display()))?;
+
+    let key = CacheKey {
+        tool_version: TOOL_VERSION,
+        no_fmt,
+    };
+    let key_bytes = serde_json::to_vec(&key).context("failed to serialize cache key")?;
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&bytes);
+    hasher.update(&key_bytes);
+    Ok(hasher.finalize().to_hex().to_string())
+}
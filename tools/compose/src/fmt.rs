@@ -0,0 +1,53 @@
+//! Runs emitted stub sources through `rustfmt` over stdin before they are
+//! written out, cleaning up the indentation left by stripping
+//! `begin_private`/`end_private` spans.
+
+use std::env;
+use std::ffi::OsString;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Format `source` with `rustfmt`, falling back to the unformatted text
+/// (with a warning on stderr) if rustfmt is unavailable or fails on it.
+pub fn format(source: String) -> String {
+    match try_format(&source) {
+        Ok(formatted) => formatted,
+        Err(err) => {
+            eprintln!("warning: {}, leaving the generated stub unformatted", err);
+            source
+        }
+    }
+}
+
+fn try_format(source: &str) -> Result<String, String> {
+    let program = env::var_os("RUSTFMT").unwrap_or_else(|| OsString::from("rustfmt"));
+
+    let mut child = Command::new(&program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("failed to launch rustfmt: {}", err))?;
+
+    // rustfmt can fill its stdout/stderr pipe buffers before we start
+    // reading, so write stdin from a separate thread to avoid deadlock.
+    let mut stdin = child.stdin.take().expect("child stdin was piped");
+    let source = source.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(source.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .map_err(|err| format!("failed to run rustfmt: {}", err))?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(format!(
+            "rustfmt exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8(output.stdout).map_err(|err| format!("rustfmt produced non-utf8 output: {}", err))
+}
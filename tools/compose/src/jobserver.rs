@@ -0,0 +1,40 @@
+//! Cooperation with an outer `make -j` invocation via the GNU Make
+//! jobserver protocol: a worker reads a byte from the shared pipe to
+//! claim a slot and writes it back when done. Falls back to a
+//! CPU-sized private jobserver when `MAKEFLAGS` isn't set, behind the
+//! same acquire/release API either way.
+
+use anyhow::{Context, Result};
+
+/// A claimed job slot. Dropping it returns the token to the jobserver.
+pub struct JobToken(#[allow(dead_code)] jobserver::Acquired);
+
+/// Either the jobserver advertised by an outer `make -j`, or a private
+/// one sized to the CPU count.
+pub struct JobServer {
+    client: jobserver::Client,
+}
+
+impl JobServer {
+    /// Parse `MAKEFLAGS` for a jobserver; fall back to one token per CPU
+    /// (minus the slot the current process already holds).
+    pub fn from_env_or_cpus() -> Result<Self> {
+        let client = match unsafe { jobserver::Client::from_env() } {
+            Some(client) => client,
+            None => {
+                let slots = num_cpus::get().saturating_sub(1).max(1);
+                jobserver::Client::new(slots)
+                    .context("failed to start fallback jobserver")?
+            }
+        };
+        Ok(JobServer { client })
+    }
+
+    /// Block until a job slot is available.
+    pub fn acquire(&self) -> Result<JobToken> {
+        self.client
+            .acquire()
+            .map(JobToken)
+            .context("failed to acquire jobserver token")
+    }
+}
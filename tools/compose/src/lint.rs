@@ -0,0 +1,114 @@
+//! Sanity lints run over every generated `.rs` file: a hard failure if
+//! any `compose::` directive token survived into the public output,
+//! plus a configurable line-length check. Reports every violation
+//! found rather than stopping at the first.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+fn default_max_line_width() -> usize {
+    100
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LintConfig {
+    /// Longest allowed line, in columns.
+    #[serde(default = "default_max_line_width")]
+    pub max_line_width: usize,
+    /// Fail if a `compose::` directive survived into the output.
+    #[serde(default = "default_true")]
+    pub leftover_tokens: bool,
+    /// Fail if a line exceeds `max_line_width`.
+    #[serde(default = "default_true")]
+    pub line_length: bool,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            max_line_width: default_max_line_width(),
+            leftover_tokens: true,
+            line_length: true,
+        }
+    }
+}
+
+struct Violation {
+    file: PathBuf,
+    line: usize,
+    message: String,
+}
+
+/// Walk `out_path` and check every `.rs` file against `config`, printing
+/// every violation found. Returns an error if any were found.
+pub fn check(out_path: &Path, config: &LintConfig) -> Result<()> {
+    let mut violations = vec![];
+    walk(out_path, config, &mut violations)?;
+
+    for violation in &violations {
+        eprintln!(
+            "{}:{}: {}",
+            violation.file.display(),
+            violation.line,
+            violation.message
+        );
+    }
+
+    if !violations.is_empty() {
+        bail!("{} lint violation(s) in generated output", violations.len());
+    }
+    Ok(())
+}
+
+fn walk(dir: &Path, config: &LintConfig, violations: &mut Vec<Violation>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("failed to read dir {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read entry in dir {}", dir.display()))?
+            .path();
+
+        if path.is_dir() {
+            walk(&path, config, violations)?;
+        } else if path.extension().map(|ext| ext == "rs").unwrap_or(false) {
+            check_file(&path, config, violations)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_file(path: &Path, config: &LintConfig, violations: &mut Vec<Violation>) -> Result<()> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read {} for linting", path.display()))?;
+
+    for (i, line) in content.lines().enumerate() {
+        if config.leftover_tokens && line.contains("compose::") {
+            violations.push(Violation {
+                file: path.to_path_buf(),
+                line: i + 1,
+                message: "leftover `compose::` directive in generated output".to_owned(),
+            });
+        }
+
+        let width = line.chars().count();
+        if config.line_length && width > config.max_line_width {
+            violations.push(Violation {
+                file: path.to_path_buf(),
+                line: i + 1,
+                message: format!(
+                    "line is {} columns wide, over the {} limit",
+                    width, config.max_line_width
+                ),
+            });
+        }
+    }
+    Ok(())
+}
@@ -1,28 +1,57 @@
 use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     ffi::OsString,
     fs,
     io::Read,
     path::{Path, PathBuf},
 };
 
+mod cache;
+mod fmt;
+mod jobserver;
+mod lint;
+mod pool;
+mod verify;
+
+use cache::Cache;
+use crate::jobserver::JobServer;
+use lint::LintConfig;
+use pool::FileTask;
+use verify::{VerifyMode, VerifyOpts};
+
 const CONFIG_NAME: &str = ".compose.yml";
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct Config {
     entries: Vec<PathBuf>,
     no_copy: Vec<PathBuf>,
     no_remove: Vec<PathBuf>,
     workspace_tools: Vec<PathBuf>,
+    /// Per-task overrides for `verify`, keyed by entry path.
+    #[serde(default)]
+    verify_modes: HashMap<PathBuf, VerifyMode>,
+    /// Sanity lints run over the generated output.
+    #[serde(default)]
+    lint: LintConfig,
 }
 
 #[derive(StructOpt, Debug)]
 #[structopt()]
-struct Opts {
+enum Command {
+    /// Compose the public repo from the private one. There is no default
+    /// subcommand: `compose` must be named explicitly.
+    Compose(ComposeOpts),
+    /// Build and test the composed public repo like a grading oracle.
+    Verify(VerifyOpts),
+}
+
+#[derive(StructOpt, Debug, Serialize)]
+#[structopt()]
+struct ComposeOpts {
     /// Path to the private repo.
     #[structopt(short = "i", long = "in-path")]
     in_path: PathBuf,
@@ -38,6 +67,12 @@ struct Opts {
     /// Add given tools to Cargo.toml
     #[structopt(short = "t", long = "add-tool")]
     add_tools: Vec<PathBuf>,
+    /// Bypass the content-addressed cache and reprocess every file.
+    #[structopt(long = "force")]
+    force: bool,
+    /// Skip running generated stubs through rustfmt.
+    #[structopt(long = "no-fmt")]
+    no_fmt: bool,
 }
 
 enum TokenKind {
@@ -188,7 +223,7 @@ fn process_source(src: String) -> Result<String> {
     Ok(dst)
 }
 
-fn process_file(in_path: &Path, out_path: &Path) -> Result<()> {
+fn process_file(in_path: &Path, out_path: &Path, no_fmt: bool) -> Result<()> {
     let out_dir = out_path.parent().unwrap();
     fs::create_dir_all(out_dir)
         .with_context(|| format!("failed to create dir {}", out_dir.display()))?;
@@ -200,8 +235,11 @@ fn process_file(in_path: &Path, out_path: &Path) -> Result<()> {
     {
         let content = fs::read_to_string(in_path)
             .with_context(|| format!("failed to read file {}", in_path.display()))?;
-        let new_content = process_source(content)
+        let mut new_content = process_source(content)
             .with_context(|| format!("failed to process file {}", in_path.display()))?;
+        if !no_fmt {
+            new_content = fmt::format(new_content);
+        }
         fs::write(out_path, new_content)
             .with_context(|| format!("failed to write file {}", out_path.display()))?;
     } else {
@@ -217,10 +255,11 @@ fn process_file(in_path: &Path, out_path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn process_dir(
+fn collect_tasks(
     in_path: &Path,
     out_path: &Path,
     excluded_entries: &HashSet<OsString>,
+    tasks: &mut Vec<FileTask>,
 ) -> Result<()> {
     let dir = fs::read_dir(in_path)
         .with_context(|| format!("failed to read dir {}", in_path.display()))?;
@@ -238,14 +277,44 @@ fn process_dir(
         let new_out_path = out_path.join(&name);
 
         if new_in_path.is_dir() {
-            process_dir(&new_in_path, &new_out_path, excluded_entries)?;
+            collect_tasks(&new_in_path, &new_out_path, excluded_entries, tasks)?;
         } else {
-            process_file(&new_in_path, &new_out_path)?;
+            tasks.push(FileTask {
+                in_path: new_in_path,
+                out_path: new_out_path,
+            });
         }
     }
     Ok(())
 }
 
+fn process_dir(
+    in_path: &Path,
+    out_path: &Path,
+    excluded_entries: &HashSet<OsString>,
+    jobs: &JobServer,
+    cache: &mut Cache,
+    args: &ComposeOpts,
+) -> Result<()> {
+    let mut tasks = vec![];
+    collect_tasks(in_path, out_path, excluded_entries, &mut tasks)?;
+
+    let mut stale = vec![];
+    for task in tasks {
+        if !cache.is_fresh(&task.in_path, &task.out_path, args.no_fmt)? {
+            stale.push(task);
+        }
+    }
+
+    pool::run(stale.clone(), jobs, args.no_fmt)?;
+
+    for task in &stale {
+        cache.record(&task.in_path, &task.out_path, args.no_fmt)?;
+    }
+
+    Ok(())
+}
+
 fn read_config(path: &Path) -> Result<Config> {
     let mut file = fs::File::open(path).context(format!("failed to open {}", path.display()))?;
 
@@ -256,7 +325,7 @@ fn read_config(path: &Path) -> Result<Config> {
     serde_yaml::from_slice(&buffer).context("failed to parse config")
 }
 
-fn process_entries(args: &Opts, config: &Config) -> Result<()> {
+fn process_entries(args: &ComposeOpts, config: &Config, jobs: &JobServer, cache: &mut Cache) -> Result<()> {
     let excluded_entries = config
         .no_copy
         .iter()
@@ -267,16 +336,17 @@ fn process_entries(args: &Opts, config: &Config) -> Result<()> {
         let in_path = args.in_path.join(entry);
         let out_path = args.out_path.join(entry);
         if in_path.is_dir() {
-            process_dir(&in_path, &out_path, &excluded_entries)?;
-        } else {
-            process_file(&in_path, &out_path)?;
+            process_dir(&in_path, &out_path, &excluded_entries, jobs, cache, args)?;
+        } else if !cache.is_fresh(&in_path, &out_path, args.no_fmt)? {
+            process_file(&in_path, &out_path, args.no_fmt)?;
+            cache.record(&in_path, &out_path, args.no_fmt)?;
         }
     }
 
     Ok(())
 }
 
-fn prune_entries(args: &Opts, config: &Config) -> Result<()> {
+fn prune_entries(args: &ComposeOpts, config: &Config) -> Result<()> {
     let spare = config
         .entries
         .iter()
@@ -292,7 +362,8 @@ fn prune_entries(args: &Opts, config: &Config) -> Result<()> {
             .with_context(|| format!("failed to read entry in dir {}", args.out_path.display()))?
             .path();
 
-        if !spare.contains(Path::new(path.file_name().unwrap())) {
+        let name = path.file_name().unwrap();
+        if name.to_str() != Some(cache::CACHE_FILE) && !spare.contains(Path::new(name)) {
             let res = if path.is_dir() {
                 fs::remove_dir_all(&path)
             } else {
@@ -305,7 +376,7 @@ fn prune_entries(args: &Opts, config: &Config) -> Result<()> {
     Ok(())
 }
 
-fn write_root_cargo(args: &Opts, config: &Config) -> Result<()> {
+fn write_root_cargo(args: &ComposeOpts, config: &Config) -> Result<()> {
     let mut tasks = vec![];
     for entry in config.entries.iter() {
         if args.out_path.join(entry).join("Cargo.toml").exists() {
@@ -343,23 +414,93 @@ members = [
     Ok(())
 }
 
-fn do_main(args: Opts) -> Result<()> {
+fn do_compose(args: ComposeOpts) -> Result<()> {
     let config = read_config(&args.in_path.join(CONFIG_NAME)).context("failed to read config")?;
 
     if !args.no_process {
-        process_entries(&args, &config).context("failed to process entries")?;
+        let jobs = JobServer::from_env_or_cpus().context("failed to set up jobserver")?;
+        let mut cache = Cache::load(&args);
+        process_entries(&args, &config, &jobs, &mut cache).context("failed to process entries")?;
+        cache.save().context("failed to save cache manifest")?;
     }
 
     prune_entries(&args, &config).context("failed to prune entries")?;
 
-    write_root_cargo(&args, &config).context("failed to write root Cargo.toml")
+    write_root_cargo(&args, &config).context("failed to write root Cargo.toml")?;
+
+    lint::check(&args.out_path, &config.lint).context("output failed sanity lints")
+}
+
+fn do_verify(args: VerifyOpts) -> Result<()> {
+    let config = read_config(&args.in_path.join(CONFIG_NAME)).context("failed to read config")?;
+    verify::run(&args, &config)
 }
 
 fn main() {
-    let args = Opts::from_args();
+    let command = Command::from_args();
+
+    let result = match command {
+        Command::Compose(args) => do_compose(args),
+        Command::Verify(args) => do_verify(args),
+    };
 
-    if let Err(err) = do_main(args) {
+    if let Err(err) = result {
         eprintln!("Error: {:#}", err);
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("compose-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn opts(in_path: PathBuf, out_path: PathBuf) -> ComposeOpts {
+        ComposeOpts {
+            in_path,
+            out_path,
+            no_process: false,
+            spare: vec![],
+            add_tools: vec![],
+            force: false,
+            no_fmt: true,
+        }
+    }
+
+    #[test]
+    fn second_run_skips_unchanged_files_and_keeps_the_cache() {
+        let in_path = temp_dir("in");
+        let out_path = temp_dir("out");
+
+        fs::write(
+            in_path.join(CONFIG_NAME),
+            "entries: [src]\nno_copy: []\nno_remove: []\nworkspace_tools: []\n",
+        )
+        .unwrap();
+        fs::create_dir_all(in_path.join("src")).unwrap();
+        fs::write(in_path.join("src/lib.rs"), "pub fn hello() {}\n").unwrap();
+
+        do_compose(opts(in_path.clone(), out_path.clone())).unwrap();
+        let sidecar = out_path.join(cache::CACHE_FILE);
+        assert!(sidecar.exists(), "prune_entries must not remove the cache sidecar");
+        let first_mtime = fs::metadata(out_path.join("src/lib.rs")).unwrap().modified().unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        do_compose(opts(in_path.clone(), out_path.clone())).unwrap();
+        let second_mtime = fs::metadata(out_path.join("src/lib.rs")).unwrap().modified().unwrap();
+
+        assert_eq!(
+            first_mtime, second_mtime,
+            "an unchanged input should not be reprocessed on the second run"
+        );
+
+        fs::remove_dir_all(&in_path).unwrap();
+        fs::remove_dir_all(&out_path).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+//! A work-stealing thread pool that processes independent files
+//! concurrently, respecting the job slots handed out by
+//! [`crate::jobserver::JobServer`].
+//!
+//! All file tasks are pushed onto a shared [`Injector`] up front; each
+//! worker thread steals tasks off it until it runs dry, so a directory
+//! with a long tail of small files doesn't starve behind one big one.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::Result;
+use crossbeam_deque::{Injector, Steal};
+
+use crate::jobserver::JobServer;
+use crate::process_file;
+
+/// A single file to copy or process, with its input/output paths.
+#[derive(Clone)]
+pub struct FileTask {
+    pub in_path: PathBuf,
+    pub out_path: PathBuf,
+}
+
+/// Run `process_file` over every task, spreading work across a pool of
+/// worker threads bounded by `jobs`. Returns the first error encountered,
+/// if any, after all workers have finished.
+pub fn run(tasks: Vec<FileTask>, jobs: &JobServer, no_fmt: bool) -> Result<()> {
+    let injector = Injector::new();
+    for task in tasks {
+        injector.push(task);
+    }
+
+    let errors: Mutex<Vec<anyhow::Error>> = Mutex::new(Vec::new());
+    let workers = num_cpus::get().max(1);
+
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            // Worker 0 never calls `jobs.acquire()`, so it always runs on
+            // the implicit slot the process already holds: total
+            // concurrency is at most `jobs` + 1 for the whole run, not
+            // just its first task, and never more than `workers`.
+            let uses_implicit_slot = worker == 0;
+            scope.spawn(move || loop {
+                let task = match injector.steal() {
+                    Steal::Success(task) => task,
+                    Steal::Empty => break,
+                    Steal::Retry => continue,
+                };
+
+                let result = if uses_implicit_slot {
+                    process_file(&task.in_path, &task.out_path, no_fmt)
+                } else {
+                    jobs.acquire()
+                        .and_then(|_token| process_file(&task.in_path, &task.out_path, no_fmt))
+                };
+                if let Err(err) = result {
+                    errors.lock().unwrap().push(err);
+                }
+            });
+        }
+    });
+
+    match errors.into_inner().unwrap().into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,131 @@
+//! A `verify` subcommand that treats the composed public repo as a
+//! grading oracle, modeled on rustc's compiletest mode system
+//! (run-pass / compile-fail / run-fail): the default `RunFail` mode
+//! checks that the stub builds but its tests fail, while `CompileFail`
+//! skips the stub test-suite check for tasks expected not to compile.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::Config;
+
+#[derive(StructOpt, Debug)]
+#[structopt()]
+pub struct VerifyOpts {
+    /// Path to the private repo.
+    #[structopt(short = "i", long = "in-path")]
+    pub in_path: PathBuf,
+    /// Path to the composed public repo to verify.
+    #[structopt(short = "o", long = "out-path")]
+    pub out_path: PathBuf,
+}
+
+/// Expected behavior of a task's public stub, overridable per-task via
+/// `verify_modes` in `.compose.yml`.
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyMode {
+    /// The stub must build, but its tests must fail (the default).
+    #[default]
+    RunFail,
+    /// The stub is expected not to compile at all.
+    CompileFail,
+}
+
+struct TaskReport {
+    entry: PathBuf,
+    mode: VerifyMode,
+    stub_builds: bool,
+    stub_tests_fail: Option<bool>,
+    private_tests_pass: bool,
+}
+
+impl TaskReport {
+    fn ok(&self) -> bool {
+        let stub_ok = match self.mode {
+            VerifyMode::RunFail => self.stub_builds && self.stub_tests_fail == Some(true),
+            VerifyMode::CompileFail => !self.stub_builds,
+        };
+        stub_ok && self.private_tests_pass
+    }
+}
+
+/// Run the three-way build/test check over every task crate and print a
+/// pass/fail summary; returns an error if any task failed.
+pub fn run(args: &VerifyOpts, config: &Config) -> Result<()> {
+    let mut reports = vec![];
+
+    for entry in &config.entries {
+        let private_dir = args.in_path.join(entry);
+        let public_dir = args.out_path.join(entry);
+        if !public_dir.join("Cargo.toml").exists() {
+            continue;
+        }
+
+        let mode = config.verify_modes.get(entry).copied().unwrap_or_default();
+        let stub_builds = cargo_succeeds(&public_dir, "build")?;
+        let stub_tests_fail = if mode == VerifyMode::RunFail && stub_builds {
+            Some(!cargo_succeeds(&public_dir, "test")?)
+        } else {
+            None
+        };
+        let private_tests_pass = cargo_succeeds(&private_dir, "test")?;
+
+        reports.push(TaskReport {
+            entry: entry.clone(),
+            mode,
+            stub_builds,
+            stub_tests_fail,
+            private_tests_pass,
+        });
+    }
+
+    let mut failed = 0;
+    for report in &reports {
+        if report.ok() {
+            println!("[PASS] {}", report.entry.display());
+        } else {
+            failed += 1;
+            println!("[FAIL] {}", report.entry.display());
+            match report.mode {
+                VerifyMode::RunFail => {
+                    if !report.stub_builds {
+                        println!("       stub failed to `cargo build`");
+                    } else if report.stub_tests_fail != Some(true) {
+                        println!("       stub tests did not fail (stripped code still passes)");
+                    }
+                }
+                VerifyMode::CompileFail => {
+                    if report.stub_builds {
+                        println!("       stub built, but `compile-fail` expects it not to");
+                    }
+                }
+            }
+            if !report.private_tests_pass {
+                println!("       private sources failed `cargo test`");
+            }
+        }
+    }
+
+    println!("{}/{} tasks passed", reports.len() - failed, reports.len());
+
+    if failed > 0 {
+        bail!("{} task(s) failed verification", failed);
+    }
+    Ok(())
+}
+
+fn cargo_succeeds(dir: &Path, subcommand: &str) -> Result<bool> {
+    let status = Command::new("cargo")
+        .arg(subcommand)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| format!("failed to run `cargo {}` in {}", subcommand, dir.display()))?;
+    Ok(status.success())
+}